@@ -0,0 +1,91 @@
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::NonNull;
+use critical_section::CriticalSection;
+use super::{Implies, Init, STC};
+
+/// Single-thread context for the duration of a critical section from the `critical-section`
+/// crate. Implements STC.
+///
+/// The lifetime of the critical section token is kept, so the context cannot be used after
+/// `critical_section::with` returns.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{CsContext, SingleThreadRefCell};
+/// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+///
+/// critical_section::with(|cs| {
+///     let ctx = CsContext::new(cs);
+///     *G_INT.borrow_mut(&ctx) += 1;
+/// });
+/// ```
+///
+/// The context does not outlive the critical section:
+/// ```compile_fail
+/// use concurrency_context::{CsContext, SingleThreadRefCell};
+/// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+///
+/// let ctx = critical_section::with(|cs| CsContext::new(cs));
+/// *G_INT.borrow_mut(&ctx) += 1;
+/// ```
+pub struct CsContext<'cs> {
+    _cs: CriticalSection<'cs>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'cs> CsContext<'cs> {
+    #[inline]
+    pub fn new(cs: CriticalSection<'cs>) -> CsContext<'cs> {
+        CsContext {
+            _cs: cs,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// A critical section may be entered on any thread, so the context is not bound to one.
+unsafe impl<'cs> STC for CsContext<'cs> {
+    const THREAD_BOUND: bool = false;
+}
+
+// Checked for the dangling reference returned by `Implies::as_weaker`
+const _: () = assert!(mem::size_of::<CsContext<'static>>() == 0);
+
+unsafe impl<'cs> Implies<CsContext<'cs>> for Init {
+    #[inline]
+    fn as_weaker(&self) -> &CsContext<'cs> {
+        // The critical section token cannot be created in a constant, but the context is zero-sized
+        // so any aligned pointer is a valid reference to it
+        unsafe { &*NonNull::dangling().as_ptr() }
+    }
+}
+
+#[test]
+fn test_cs_context() {
+    use SingleThreadRefCell;
+    static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+
+    critical_section::with(|cs| {
+        let ctx = CsContext::new(cs);
+        *G_INT.borrow_mut(&ctx) += 1;
+    });
+    critical_section::with(|cs| {
+        assert_eq!(*G_INT.borrow(&CsContext::new(cs)), 6);
+    });
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_cs_context_threads() {
+    use SingleThreadRefCell;
+    static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+
+    critical_section::with(|cs| *G_INT.borrow_mut(&CsContext::new(cs)) += 1);
+    std::thread::spawn(|| {
+        critical_section::with(|cs| *G_INT.borrow_mut(&CsContext::new(cs)) += 1);
+    }).join().unwrap();
+    critical_section::with(|cs| {
+        assert_eq!(*G_INT.borrow(&CsContext::new(cs)), 7);
+    });
+}
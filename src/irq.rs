@@ -0,0 +1,170 @@
+#[cfg(any(test, feature = "mock"))]
+use core::cell::Cell;
+use core::marker::PhantomData;
+#[cfg(any(test, feature = "mock"))]
+use mutex::RawSpinlock;
+use super::{Implies, Init, STC};
+
+/// Architecture hooks for masking interrupts, used by `IrqGuard`.
+///
+/// # Safety
+///
+/// Between `save_and_disable` and the matching `restore`, nothing else may run concurrently with
+/// the calling code. This holds for disabling interrupts on a uniprocessor system, but not on SMP
+/// where other CPUs keep running.
+pub unsafe trait IrqBackend {
+    /// Saved interrupt state, typically the previous value of the interrupt flag.
+    type State: Copy;
+
+    /// Disables interrupts and returns the previous state.
+    fn save_and_disable() -> Self::State;
+
+    /// Restores the interrupt state returned by `save_and_disable`.
+    ///
+    /// # Safety
+    ///
+    /// Must be called at most once for each state, in reverse order of `save_and_disable`.
+    unsafe fn restore(state: Self::State);
+}
+
+/// Single-thread context that exists while interrupts are disabled. Implements STC.
+///
+/// This is only obtained by borrowing it from an `IrqGuard`, so it cannot outlive the section in
+/// which interrupts are masked.
+pub struct IrqDisabled {
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl STC for IrqDisabled {}
+
+unsafe impl Implies<IrqDisabled> for Init {
+    #[inline]
+    fn as_weaker(&self) -> &IrqDisabled {
+        &IrqDisabled { _not_send: PhantomData }
+    }
+}
+
+/// Disables interrupts on creation and restores the previous state when dropped.
+///
+/// Guards may be nested. Each one restores the state that was current when it was created, so they
+/// must be dropped in reverse order of creation. `with` takes care of this with a closure, while
+/// `disable` leaves it to the caller.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{IrqBackend, IrqGuard, SingleThreadRefCell};
+/// # struct Arch;
+/// # unsafe impl IrqBackend for Arch {
+/// #     type State = ();
+/// #     fn save_and_disable() {}
+/// #     unsafe fn restore(_state: ()) {}
+/// # }
+/// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+///
+/// IrqGuard::<Arch>::with(|irq| *G_INT.borrow_mut(irq) += 1);
+/// ```
+pub struct IrqGuard<B: IrqBackend> {
+    state: B::State,
+    context: IrqDisabled,
+    _backend: PhantomData<B>,
+}
+
+impl<B: IrqBackend> IrqGuard<B> {
+    /// Runs `f` with interrupts disabled and restores the previous state afterwards.
+    #[inline]
+    pub fn with<R, F: FnOnce(&IrqDisabled) -> R>(f: F) -> R {
+        let guard = unsafe { IrqGuard::<B>::disable() };
+        f(guard.context())
+    }
+
+    /// Saves the interrupt state and disables interrupts.
+    ///
+    /// # Safety
+    ///
+    /// Guards must be dropped in reverse order of creation. Dropping an outer guard first would
+    /// re-enable interrupts while the context of the inner one is still in use.
+    #[inline]
+    pub unsafe fn disable() -> IrqGuard<B> {
+        IrqGuard {
+            state: B::save_and_disable(),
+            context: IrqDisabled { _not_send: PhantomData },
+            _backend: PhantomData,
+        }
+    }
+
+    /// Returns the context for the section in which interrupts are disabled.
+    #[inline]
+    pub fn context(&self) -> &IrqDisabled {
+        &self.context
+    }
+}
+
+impl<B: IrqBackend> Drop for IrqGuard<B> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { B::restore(self.state) }
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+static MOCK_IRQ_LOCK: RawSpinlock = RawSpinlock::new();
+
+#[cfg(any(test, feature = "mock"))]
+std::thread_local! {
+    static MOCK_IRQ_ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Software interrupt backend for testing on a host.
+///
+/// Each thread has its own interrupt flag, and disabling interrupts takes a global lock until they
+/// are enabled again. As on a uniprocessor, at most one thread at a time runs with interrupts
+/// disabled.
+#[cfg(any(test, feature = "mock"))]
+#[cfg_attr(feature = "nightly", doc(cfg(feature = "mock")))]
+pub struct MockIrq;
+
+#[cfg(any(test, feature = "mock"))]
+impl MockIrq {
+    /// Returns whether interrupts are currently enabled.
+    pub fn enabled() -> bool {
+        MOCK_IRQ_ENABLED.with(Cell::get)
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+unsafe impl IrqBackend for MockIrq {
+    type State = bool;
+
+    fn save_and_disable() -> bool {
+        let state = MockIrq::enabled();
+        if state {
+            MOCK_IRQ_LOCK.lock();
+            MOCK_IRQ_ENABLED.with(|enabled| enabled.set(false));
+        }
+        state
+    }
+
+    unsafe fn restore(state: bool) {
+        if state {
+            MOCK_IRQ_ENABLED.with(|enabled| enabled.set(true));
+            MOCK_IRQ_LOCK.unlock();
+        }
+    }
+}
+
+#[test]
+fn test_irq_guard() {
+    use SingleThreadRefCell;
+    static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+
+    assert!(MockIrq::enabled());
+    {
+        let outer = unsafe { IrqGuard::<MockIrq>::disable() };
+        assert!(!MockIrq::enabled());
+        IrqGuard::<MockIrq>::with(|inner| *G_INT.borrow_mut(inner) += 1);
+        assert!(!MockIrq::enabled());
+        assert_eq!(*G_INT.borrow(outer.context()), 6);
+        assert!(std::thread::spawn(MockIrq::enabled).join().unwrap());
+    }
+    assert!(MockIrq::enabled());
+}
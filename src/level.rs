@@ -0,0 +1,223 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use mutex::RawSpinlock;
+use super::Init;
+
+/// Context of a thread whose most recently acquired `OrderedMutex` is at level `N`.
+///
+/// Locks are acquired with a mutable borrow of the context of a lower level and yield the context
+/// of their own level, so a thread can only acquire locks in increasing order of level. Acquiring
+/// them out of order does not compile, which rules out deadlocks between ordered locks.
+///
+/// Levels 1 to 15 are available for locks, and creating an `OrderedMutex` of any other level fails
+/// to compile. Level 0 is the bottom for threads that hold no ordered lock, and `Init` is below every
+/// level.
+pub struct Level<const N: u8> {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Level<0> {
+    /// Returns the bottom context for a thread that holds no ordered lock, typically at the start
+    /// of the thread.
+    ///
+    /// # Safety
+    ///
+    /// The current thread must not hold any `OrderedMutex`, and no other `Level` or `Init` may
+    /// exist on it for as long as the returned context is alive. Otherwise locks could be acquired
+    /// out of order.
+    #[inline]
+    pub unsafe fn root() -> Level<0> {
+        Level { _not_send: PhantomData }
+    }
+}
+
+/// Implemented by contexts that are lower in the lock order than `H`.
+///
+/// # Safety
+///
+/// Must only be implemented for pairs of levels that are consistent with a total order.
+pub unsafe trait Below<H> {}
+
+unsafe impl<const N: u8> Below<Level<N>> for Init {}
+
+macro_rules! impl_below {
+    ($low:tt $($high:tt)*) => {
+        $(unsafe impl Below<Level<$high>> for Level<$low> {})*
+        impl_below!($($high)*);
+    };
+    () => {};
+}
+
+impl_below!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
+/// A spinlock at level `N` of the lock order.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{Level, OrderedMutex};
+/// static DEVICES: OrderedMutex<u32, 1> = OrderedMutex::new(0);
+/// static QUEUE: OrderedMutex<u32, 2> = OrderedMutex::new(0);
+///
+/// let mut root = unsafe { Level::root() };
+/// let mut devices = DEVICES.lock(&mut root);
+/// let (count, level) = devices.split();
+/// *QUEUE.lock(level) += *count;
+/// ```
+///
+/// Locks cannot be acquired in decreasing order of level:
+/// ```compile_fail
+/// use concurrency_context::{Level, OrderedMutex};
+/// static DEVICES: OrderedMutex<u32, 1> = OrderedMutex::new(0);
+/// static QUEUE: OrderedMutex<u32, 2> = OrderedMutex::new(0);
+///
+/// let mut root = unsafe { Level::root() };
+/// let mut queue = QUEUE.lock(&mut root);
+/// let (count, level) = queue.split();
+/// *DEVICES.lock(level) += *count;
+/// ```
+///
+/// Nor can two locks of the same level be held at once:
+/// ```compile_fail
+/// use concurrency_context::{Level, OrderedMutex};
+/// static A: OrderedMutex<u32, 1> = OrderedMutex::new(0);
+/// static B: OrderedMutex<u32, 1> = OrderedMutex::new(0);
+///
+/// let mut root = unsafe { Level::root() };
+/// let mut a = A.lock(&mut root);
+/// let (_, level) = a.split();
+/// let b = B.lock(level);
+/// ```
+///
+/// Level 0 is reserved for `Level::root`:
+/// ```compile_fail
+/// use concurrency_context::OrderedMutex;
+/// static ROOT: OrderedMutex<u32, 0> = OrderedMutex::new(0);
+/// ```
+///
+/// Levels above 15 are rejected:
+/// ```compile_fail
+/// use concurrency_context::OrderedMutex;
+/// static TOO_HIGH: OrderedMutex<u32, 16> = OrderedMutex::new(0);
+/// ```
+pub struct OrderedMutex<T, const N: u8> {
+    lock: RawSpinlock,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send, const N: u8> Sync for OrderedMutex<T, N> {}
+
+impl<T, const N: u8> OrderedMutex<T, N> {
+    #[inline]
+    pub const fn new(value: T) -> OrderedMutex<T, N> {
+        const { assert!(1 <= N && N <= 15, "OrderedMutex level must be between 1 and 15") };
+        OrderedMutex {
+            lock: RawSpinlock::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the lock, spinning until it is available. The context of the lower level stays
+    /// borrowed until the guard is dropped.
+    #[inline]
+    pub fn lock<'a, 'c, C: Below<Level<N>>>(&'a self, _context: &'c mut C) -> OrderedMutexGuard<'a, 'c, T, C, N> {
+        self.lock.lock();
+        OrderedMutexGuard {
+            mutex: self,
+            level: Level { _not_send: PhantomData },
+            _context: PhantomData,
+        }
+    }
+
+    /// Acquires the lock if it is available.
+    #[inline]
+    pub fn try_lock<'a, 'c, C: Below<Level<N>>>(&'a self, _context: &'c mut C) -> Option<OrderedMutexGuard<'a, 'c, T, C, N>> {
+        if self.lock.try_lock() {
+            Some(OrderedMutexGuard {
+                mutex: self,
+                level: Level { _not_send: PhantomData },
+                _context: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// Holds an `OrderedMutex` at level `N` locked until dropped.
+pub struct OrderedMutexGuard<'a, 'c, T: 'a, C: 'c, const N: u8> {
+    mutex: &'a OrderedMutex<T, N>,
+    level: Level<N>,
+    _context: PhantomData<&'c mut C>,
+}
+
+impl<'a, 'c, T, C, const N: u8> OrderedMutexGuard<'a, 'c, T, C, N> {
+    /// Returns the context of level `N`, for acquiring locks of higher levels.
+    #[inline]
+    pub fn level(&mut self) -> &mut Level<N> {
+        &mut self.level
+    }
+
+    /// Returns the data and the context of level `N` at the same time.
+    #[inline]
+    pub fn split(&mut self) -> (&mut T, &mut Level<N>) {
+        (unsafe { &mut *self.mutex.value.get() }, &mut self.level)
+    }
+}
+
+impl<'a, 'c, T, C, const N: u8> Deref for OrderedMutexGuard<'a, 'c, T, C, N> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<'a, 'c, T, C, const N: u8> DerefMut for OrderedMutexGuard<'a, 'c, T, C, N> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<'a, 'c, T, C, const N: u8> Drop for OrderedMutexGuard<'a, 'c, T, C, N> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { self.mutex.lock.unlock() };
+    }
+}
+
+impl<'a, 'c, T: fmt::Debug, C, const N: u8> fmt::Debug for OrderedMutexGuard<'a, 'c, T, C, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_ordered_mutex() {
+    static LOW: OrderedMutex<u32, 1> = OrderedMutex::new(1);
+    static HIGH: OrderedMutex<u32, 3> = OrderedMutex::new(2);
+
+    let mut init = unsafe { Init::new() };
+    {
+        let mut high = HIGH.lock(&mut init);
+        *high += 1;
+    }
+    let mut low = LOW.lock(&mut init);
+    let (value, level) = low.split();
+    let mut high = HIGH.try_lock(level).unwrap();
+    *high += *value;
+    assert_eq!(*high, 4);
+}
@@ -1,18 +1,289 @@
+//! Cells for static data that is accessed without concurrency, with the absence of concurrency
+//! proven by a context value.
+//!
+//! # Minimum supported Rust version
+//!
+//! The crate builds on stable Rust 1.83 or later. This is what `PerCpu::new` needs to be a
+//! `const fn` for any value type, including types with interior mutability.
+//!
+//! # Features
+//!
+//! - `std`: record the owner thread of each `SingleThreadRefCell` and panic on borrows from other
+//!   threads. Borrows made with a `CsContext` are exempt.
+//! - `track-borrows`: report the site of the last borrow when a borrow conflicts.
+//! - `critical-section`: provide `CsContext` for the `critical-section` crate.
+//! - `mock`: software backends for testing contexts on a host. Requires the standard library.
+//! - `nightly`: nightly-only extras, currently marking feature-gated items in the documentation.
+//!
+//! # Unchecked builds
+//!
+//! Building with `RUSTFLAGS="--cfg concurrency_context_unchecked"` removes the borrow flag of
+//! `SingleThreadRefCell` in release builds, making conflicting borrows undefined behavior. This is
+//! a compiler flag rather than a cargo feature so that only the final build can opt in, not any
+//! crate that happens to depend on this one. `BORROWS_CHECKED` tells whether it is in effect.
+
 #![no_std]
-#![feature(const_fn)]
+#![cfg_attr(feature = "nightly", feature(doc_cfg))]
+
+#[cfg(any(test, feature = "std", feature = "mock"))]
+extern crate std;
+#[cfg(any(test, feature = "critical-section"))]
+extern crate critical_section;
 
+#[macro_use]
+mod macros;
 mod singlethread;
 pub use singlethread::*;
+#[cfg(all(concurrency_context_unchecked, not(debug_assertions)))]
+mod unchecked;
+mod irq;
+pub use irq::*;
+mod preempt;
+pub use preempt::*;
+mod percpu;
+pub use percpu::*;
+mod mutex;
+pub use mutex::*;
+mod level;
+pub use level::*;
+mod priority;
+pub use priority::*;
+#[cfg(any(test, feature = "critical-section"))]
+mod cs;
+#[cfg(any(test, feature = "critical-section"))]
+#[cfg_attr(feature = "nightly", doc(cfg(feature = "critical-section")))]
+pub use cs::*;
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once `Init::into_concurrent` has been called. Cells whose contents are shared with
+/// `Concurrent` refuse to be initialized afterwards.
+static CONCURRENT_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether a `Concurrent` token has ever been created.
+#[inline]
+pub(crate) fn concurrent_started() -> bool {
+    CONCURRENT_STARTED.load(Ordering::Acquire)
+}
+
+/// Marker trait for a single-thread context.
+///
+/// A reference to a value implementing STC is required to borrow the data in the cells of this
+/// crate. Holding one proves that no other code, including other threads, interrupt handlers and
+/// signal handlers, can run concurrently with the holder.
+///
+/// # Safety
+///
+/// Values of the implementing type must only exist while there is no concurrency. The type must
+/// be `!Send` and `!Sync` so that neither the context nor a reference to it can be moved to
+/// another thread. A `PhantomData<*const ()>` field is sufficient for this.
+pub unsafe trait STC {
+    /// Called on every borrow made with this context. Contexts that know which thread they belong
+    /// to may panic here when used from another thread. Does nothing by default.
+    #[inline]
+    fn check_thread(&self) {}
+
+    /// Whether the context is tied to the thread it was created on. With the `std` feature,
+    /// `SingleThreadRefCell` only records and checks its owner thread for borrows made with such
+    /// contexts. Contexts that are valid on any thread, like `CsContext`, set this to `false`.
+    const THREAD_BOUND: bool = true;
+}
+
+/// Marker trait for a single-thread context of which at most one value exists at a time.
+///
+/// Because the context is unique, borrowing it mutably proves exclusive access to everything it
+/// guards. This is used by `TokenCell` to check borrows at compile time.
+///
+/// # Safety
+///
+/// No two values of the implementing type may exist at the same time, in addition to the
+/// requirements of `STC`.
+pub unsafe trait ExclusiveSTC: STC {}
+
+/// A context that provides at least the guarantees of the weaker context `W`.
+///
+/// Functions that need a particular context can accept any stronger one by taking
+/// `C: Implies<W>` and calling `as_weaker`. Every context implies itself, and `Init` implies every
+/// other context of this crate because nothing else runs yet.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{Implies, Init, IrqDisabled, SingleThreadRefCell};
+/// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+///
+/// fn increment<C: Implies<IrqDisabled>>(ctx: &C) {
+///     *G_INT.borrow_mut(ctx.as_weaker()) += 1;
+/// }
+///
+/// let ctx = unsafe { Init::new() };
+/// increment(&ctx);
+/// assert_eq!(*G_INT.borrow(&ctx), 6);
+/// ```
+///
+/// # Safety
+///
+/// Whenever a value of `Self` exists, it must be valid for a value of `W` to exist.
+pub unsafe trait Implies<W> {
+    fn as_weaker(&self) -> &W;
+}
 
-pub unsafe trait STC {}
+unsafe impl<C> Implies<C> for C {
+    #[inline]
+    fn as_weaker(&self) -> &C {
+        self
+    }
+}
 
 /// Marker struct that can be constructed at the start of a program, before any threads are
 /// launched or in an OS before any concurrency is enabled. Implements STC (single-thread context).
-pub struct Init(());
+///
+/// With the `std` feature, the thread that created the context is recorded and using it from any
+/// other thread panics.
+///
+/// The CPU running `Init` is assumed to be CPU 0. Per-CPU data accessed through `Init`, for example
+/// with `PerCpu::borrow`, is that of CPU 0.
+///
+/// The context is neither `Send` nor `Sync`, so it cannot be used from a thread:
+/// ```compile_fail
+/// use concurrency_context::{Init, SingleThreadRefCell};
+/// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+///
+/// let ctx = unsafe { Init::new() };
+/// std::thread::spawn(move || *G_INT.borrow(&ctx));
+/// ```
+///
+/// Nor can a reference to it be shared with a scoped thread:
+/// ```compile_fail
+/// use concurrency_context::{Init, SingleThreadRefCell};
+/// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+///
+/// let ctx = unsafe { Init::new() };
+/// std::thread::scope(|s| {
+///     s.spawn(|| *G_INT.borrow(&ctx));
+/// });
+/// ```
+pub struct Init {
+    #[cfg(feature = "std")]
+    owner: std::thread::ThreadId,
+    _not_send: PhantomData<*const ()>,
+}
 impl Init {
+    /// Creates the context.
+    ///
+    /// # Safety
+    ///
+    /// No other threads may be running and no interrupt or signal handlers may be enabled that
+    /// access the same data, until the returned context is dropped. No other `Init` may exist at
+    /// the same time.
     pub unsafe fn new() -> Self {
-        Init(())
+        Init {
+            #[cfg(feature = "std")]
+            owner: std::thread::current().id(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Runs `f` with a context that is confined to the closure.
+    ///
+    /// Unlike `new`, the context is only lent to `f`, so it cannot be stored or moved to another
+    /// thread that outlives the call. This makes the end of the single-threaded phase explicit.
+    /// The context is lent mutably, so it can also be used with `TokenCell::borrow_mut`.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for `new` apply for the duration of the call. Confining the
+    /// context only prevents it from outliving the single-threaded phase. Whether that phase has
+    /// actually not ended yet, for example because no thread was spawned, cannot be checked, so this
+    /// stays `unsafe`.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{Init, SingleThreadRefCell, TokenCell};
+    /// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+    /// static G_TOKEN: TokenCell<i32> = TokenCell::new(1);
+    ///
+    /// let value = unsafe { Init::scope(|ctx| {
+    ///     *G_INT.borrow_mut(ctx) += 1;
+    ///     *G_TOKEN.borrow_mut(ctx) += 1;
+    ///     *G_INT.borrow(ctx) + *G_TOKEN.borrow(ctx)
+    /// }) };
+    /// assert_eq!(value, 8);
+    /// ```
+    ///
+    /// The context cannot escape the closure:
+    /// ```compile_fail
+    /// use concurrency_context::Init;
+    ///
+    /// let ctx = unsafe { Init::scope(|ctx| ctx) };
+    /// ```
+    ///
+    /// Nor can it be moved into a thread:
+    /// ```compile_fail
+    /// use concurrency_context::{Init, SingleThreadRefCell};
+    /// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+    ///
+    /// unsafe { Init::scope(|ctx| {
+    ///     std::thread::spawn(move || *G_INT.borrow(ctx))
+    /// }) };
+    /// ```
+    pub unsafe fn scope<R, F: FnOnce(&mut Init) -> R>(f: F) -> R {
+        f(&mut Init::new())
+    }
+
+    /// Ends the single-threaded phase, returning a token for the concurrent phase.
+    ///
+    /// Consuming the context means that no borrow made with it can still be alive, and no new
+    /// borrows can be made with it once concurrency has started.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{Init, SingleThreadOnceCell};
+    /// static G_NAME: SingleThreadOnceCell<&'static str> = SingleThreadOnceCell::new();
+    ///
+    /// let ctx = unsafe { Init::new() };
+    /// G_NAME.set(&ctx, "boot").unwrap();
+    /// let concurrent = ctx.into_concurrent();
+    ///
+    /// std::thread::spawn(move || {
+    ///     assert_eq!(G_NAME.get_shared(&concurrent), Some(&"boot"));
+    /// }).join().unwrap();
+    /// ```
+    ///
+    /// The context can no longer be used afterwards:
+    /// ```compile_fail
+    /// use concurrency_context::{Init, SingleThreadRefCell};
+    /// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+    ///
+    /// let ctx = unsafe { Init::new() };
+    /// let concurrent = ctx.into_concurrent();
+    /// *G_INT.borrow_mut(&ctx) += 1;
+    /// ```
+    #[inline]
+    pub fn into_concurrent(self) -> Concurrent {
+        CONCURRENT_STARTED.store(true, Ordering::Release);
+        Concurrent { _private: () }
+    }
+}
+
+unsafe impl STC for Init {
+    #[cfg(feature = "std")]
+    fn check_thread(&self) {
+        let current = std::thread::current().id();
+        if self.owner != current {
+            panic!("Init context created on thread {:?} used on thread {:?}", self.owner, current);
+        }
     }
 }
 
-unsafe impl STC for Init {}
+unsafe impl ExclusiveSTC for Init {}
+
+/// Token for the concurrent phase of a program, obtained by consuming `Init`.
+///
+/// Unlike single-thread contexts, this token can be copied and sent to any thread. It only allows
+/// reading data that was initialized during `Init` and can no longer change, such as the contents
+/// of a `SingleThreadOnceCell`. Once the token exists, such cells can no longer be initialized.
+#[derive(Clone, Copy)]
+pub struct Concurrent {
+    _private: (),
+}
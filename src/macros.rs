@@ -0,0 +1,58 @@
+/// Declares `SingleThreadRefCell` statics, optionally with accessor functions.
+///
+/// Each item is written like a `static` whose type is the type of the value inside the cell.
+/// Following the initializer with `=> name` generates a function `name(&ctx)` that borrows the
+/// cell, and `=> name, name_mut` additionally generates `name_mut(&ctx)` that borrows it mutably.
+/// The accessors have the same visibility as the static.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate concurrency_context;
+///
+/// single_thread_static! {
+///     /// Number of devices found during boot
+///     pub static DEVICES: u32 = 0 => devices, devices_mut;
+///     static NAME: &'static str = "boot" => name;
+///     static FLAGS: [bool; 4] = [false; 4];
+/// }
+///
+/// fn main() {
+///     let ctx = unsafe { concurrency_context::Init::new() };
+///     *devices_mut(&ctx) += 2;
+///     assert_eq!(*devices(&ctx), 2);
+///     assert_eq!(*name(&ctx), "boot");
+///     FLAGS.borrow_mut(&ctx)[1] = true;
+/// }
+/// ```
+#[macro_export]
+macro_rules! single_thread_static {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr => $get:ident $(, $get_mut:ident)?; $($rest:tt)*) => {
+        $crate::single_thread_static!($(#[$attr])* $vis static $name: $ty = $init;);
+
+        #[doc = concat!("Immutably borrows [`", stringify!($name), "`].")]
+        #[inline]
+        #[track_caller]
+        $vis fn $get<'b, C: $crate::STC + 'b>(context: &'b C) -> $crate::SingleThreadRef<'static, 'b, $ty, C> {
+            $name.borrow(context)
+        }
+
+        $(
+            #[doc = concat!("Mutably borrows [`", stringify!($name), "`].")]
+            #[inline]
+            #[track_caller]
+            $vis fn $get_mut<'b, C: $crate::STC + 'b>(context: &'b C) -> $crate::SingleThreadRefMut<'static, 'b, $ty, C> {
+                $name.borrow_mut(context)
+            }
+        )?
+
+        $crate::single_thread_static!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::SingleThreadRefCell<$ty> = $crate::SingleThreadRefCell::new($init);
+
+        $crate::single_thread_static!($($rest)*);
+    };
+}
@@ -0,0 +1,270 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::{BorrowError, BorrowMutError, Init, SingleThreadRef, SingleThreadRefCell, SingleThreadRefMut};
+
+/// Context proving that the lock identified by the tag type `L` is held.
+///
+/// `ContextMutexGuard` implements this for the tag of its mutex, and `Init` implements it for
+/// every tag because no other code can hold a lock yet.
+///
+/// # Safety
+///
+/// While a value of the implementing type exists, no other thread may hold the lock `L`. The type
+/// must be `!Send` and `!Sync` like an STC.
+pub unsafe trait Holds<L> {}
+
+unsafe impl<L> Holds<L> for Init {}
+
+/// Spinlock without data, shared by the mutexes of this crate.
+pub(crate) struct RawSpinlock {
+    locked: AtomicBool,
+}
+
+impl RawSpinlock {
+    #[inline]
+    pub(crate) const fn new() -> RawSpinlock {
+        RawSpinlock {
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn lock(&self) {
+        while !self.try_lock() {
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn try_lock(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    /// # Safety
+    ///
+    /// The lock must be held by the caller.
+    #[inline]
+    pub(crate) unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A spinlock whose guard is a context proving that the lock is held.
+///
+/// The tag type `L` names the lock. Besides the data in the mutex itself, any number of `LockCell`s
+/// tagged with `L` can be borrowed with the guard, so one lock can protect data spread over several
+/// statics.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{ContextMutex, LockCell};
+/// struct NetLock;
+/// static NET_UP: ContextMutex<bool, NetLock> = unsafe { ContextMutex::new(false) };
+/// static RX_PACKETS: LockCell<u64, NetLock> = LockCell::new(0);
+/// static TX_PACKETS: LockCell<u64, NetLock> = LockCell::new(0);
+///
+/// let mut net = NET_UP.lock();
+/// *net = true;
+/// *RX_PACKETS.borrow_mut(&net) += 1;
+/// *TX_PACKETS.borrow_mut(&net) += 2;
+/// assert_eq!(*RX_PACKETS.borrow(&net) + *TX_PACKETS.borrow(&net), 3);
+/// ```
+///
+/// A cell cannot be borrowed with the guard of another lock:
+/// ```compile_fail
+/// use concurrency_context::{ContextMutex, LockCell};
+/// struct NetLock;
+/// struct DiskLock;
+/// static DISK: ContextMutex<(), DiskLock> = unsafe { ContextMutex::new(()) };
+/// static RX_PACKETS: LockCell<u64, NetLock> = LockCell::new(0);
+///
+/// let disk = DISK.lock();
+/// *RX_PACKETS.borrow_mut(&disk) += 1;
+/// ```
+pub struct ContextMutex<T, L> {
+    lock: RawSpinlock,
+    value: UnsafeCell<T>,
+    _lock: PhantomData<fn() -> L>,
+}
+
+unsafe impl<T: Send, L> Sync for ContextMutex<T, L> {}
+
+impl<T, L> ContextMutex<T, L> {
+    /// Creates an unlocked mutex.
+    ///
+    /// # Safety
+    ///
+    /// No other `ContextMutex` tagged with `L` may exist, otherwise holding either of them would
+    /// allow borrowing the `LockCell`s of both.
+    #[inline]
+    pub const unsafe fn new(value: T) -> ContextMutex<T, L> {
+        ContextMutex {
+            lock: RawSpinlock::new(),
+            value: UnsafeCell::new(value),
+            _lock: PhantomData,
+        }
+    }
+
+    /// Acquires the lock, spinning until it is available.
+    #[inline]
+    pub fn lock(&self) -> ContextMutexGuard<'_, T, L> {
+        self.lock.lock();
+        ContextMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Acquires the lock if it is available.
+    #[inline]
+    pub fn try_lock(&self) -> Option<ContextMutexGuard<'_, T, L>> {
+        if self.lock.try_lock() {
+            Some(ContextMutexGuard {
+                mutex: self,
+                _not_send: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// Holds a `ContextMutex` locked until dropped. Dereferences to the data in the mutex and is a
+/// context for the `LockCell`s tagged with the same lock.
+pub struct ContextMutexGuard<'a, T: 'a, L: 'a> {
+    mutex: &'a ContextMutex<T, L>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T, L> Holds<L> for ContextMutexGuard<'a, T, L> {}
+
+impl<'a, T, L> Deref for ContextMutexGuard<'a, T, L> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<'a, T, L> DerefMut for ContextMutexGuard<'a, T, L> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<'a, T, L> Drop for ContextMutexGuard<'a, T, L> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { self.mutex.lock.unlock() };
+    }
+}
+
+impl<'a, T: fmt::Debug, L> fmt::Debug for ContextMutexGuard<'a, T, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A `SingleThreadRefCell` protected by the lock `L` instead of a single-thread context.
+///
+/// Borrowing the value requires a context implementing `Holds<L>`, usually the guard of the
+/// `ContextMutex` tagged with `L`. The guards are those of `SingleThreadRefCell` and cannot outlive
+/// the lock guard.
+pub struct LockCell<T, L> {
+    cell: SingleThreadRefCell<T>,
+    _lock: PhantomData<fn() -> L>,
+}
+
+unsafe impl<T: Send, L> Sync for LockCell<T, L> {}
+
+impl<T, L> LockCell<T, L> {
+    #[inline]
+    pub const fn new(value: T) -> LockCell<T, L> {
+        LockCell {
+            cell: SingleThreadRefCell::new(value),
+            _lock: PhantomData,
+        }
+    }
+
+    /// Immutably borrows the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn borrow<'a, 'b, C: Holds<L> + 'b>(&'a self, context: &'b C) -> SingleThreadRef<'a, 'b, T, C> {
+        self.cell.borrow_in(context)
+    }
+
+    /// Mutably borrows the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn borrow_mut<'a, 'b, C: Holds<L> + 'b>(&'a self, context: &'b C) -> SingleThreadRefMut<'a, 'b, T, C> {
+        self.cell.borrow_mut_in(context)
+    }
+
+    /// Immutably borrows the value, returning an error if it is currently mutably borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn try_borrow<'a, 'b, C: Holds<L> + 'b>(&'a self, context: &'b C) -> Result<SingleThreadRef<'a, 'b, T, C>, BorrowError> {
+        self.cell.try_borrow_in(context)
+    }
+
+    /// Mutably borrows the value, returning an error if it is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn try_borrow_mut<'a, 'b, C: Holds<L> + 'b>(&'a self, context: &'b C) -> Result<SingleThreadRefMut<'a, 'b, T, C>, BorrowMutError> {
+        self.cell.try_borrow_mut_in(context)
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.cell.into_inner()
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.cell.get_mut()
+    }
+}
+
+#[test]
+fn test_context_mutex() {
+    struct TestLock;
+    static MUTEX: ContextMutex<u32, TestLock> = unsafe { ContextMutex::new(1) };
+    static CELL: LockCell<u32, TestLock> = LockCell::new(2);
+
+    {
+        let mut guard = MUTEX.lock();
+        assert!(MUTEX.try_lock().is_none());
+        *guard += 1;
+        *CELL.borrow_mut(&guard) += *guard;
+        assert!(CELL.try_borrow(&guard).is_ok());
+    }
+    let guard = MUTEX.try_lock().unwrap();
+    assert_eq!(*guard, 2);
+    assert_eq!(*CELL.borrow(&guard), 4);
+}
@@ -0,0 +1,145 @@
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ptr;
+use core::slice;
+use super::{Implies, Init, PreemptDisabled, SingleThreadRef, SingleThreadRefCell, SingleThreadRefMut};
+#[cfg(test)]
+use super::{MockPreempt, PreemptGuard};
+
+/// One value per CPU, accessed through a context that pins the current thread to its CPU.
+///
+/// `borrow` and `borrow_mut` select the slot of the CPU recorded in the `PreemptDisabled` context.
+/// Each slot is a `SingleThreadRefCell`, so the guards are the same as for a single cell.
+///
+/// Interrupt handlers cannot access the slots, because `PreemptBackend` requires that they cannot
+/// create a `PreemptGuard`. This matters beyond borrow checking: the borrow flag of a slot is not
+/// atomic, and in unchecked builds there is no flag at all, so a handler that borrowed the slot of
+/// the code it interrupted would cause undefined behavior rather than a panic. Data shared with
+/// interrupt handlers belongs in a cell borrowed with a context that masks them, such as
+/// `IrqDisabled`.
+///
+/// During `Init`, only the boot CPU runs, so `iter_all` gives access to the slots of every CPU, for
+/// example to set them up before the secondary CPUs are started.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{Init, PerCpu, PreemptBackend, PreemptCounter, PreemptGuard};
+/// # struct Sched;
+/// # static COUNTER: PreemptCounter = PreemptCounter::new();
+/// # unsafe impl PreemptBackend for Sched {
+/// #     fn counter() -> &'static PreemptCounter { &COUNTER }
+/// #     unsafe fn disable() {}
+/// #     unsafe fn enable() {}
+/// #     fn current_cpu() -> usize { 1 }
+/// # }
+/// static TICKS: PerCpu<u64, 2> = PerCpu::new([0, 0]);
+///
+/// let init = unsafe { Init::new() };
+/// for (cpu, ticks) in TICKS.iter_all(&init).enumerate() {
+///     *ticks.borrow_mut(&init) = cpu as u64 * 100;
+/// }
+/// drop(init);
+///
+/// let guard = PreemptGuard::<Sched>::disable();
+/// *TICKS.borrow_mut(guard.context()) += 1;
+/// assert_eq!(*TICKS.borrow(guard.context()), 101);
+/// ```
+pub struct PerCpu<T, const N: usize> {
+    slots: [SingleThreadRefCell<T>; N],
+}
+
+impl<T, const N: usize> PerCpu<T, N> {
+    const UNINIT: MaybeUninit<SingleThreadRefCell<T>> = MaybeUninit::uninit();
+
+    /// Creates the container with `values[i]` as the value of CPU `i`.
+    #[inline]
+    pub const fn new(values: [T; N]) -> PerCpu<T, N> {
+        let values = ManuallyDrop::new(values);
+        let values = ptr::addr_of!(values) as *const T;
+        let mut slots = [Self::UNINIT; N];
+        let mut i = 0;
+        while i < N {
+            slots[i] = MaybeUninit::new(SingleThreadRefCell::new(unsafe { ptr::read(values.add(i)) }));
+            i += 1;
+        }
+        PerCpu {
+            slots: unsafe { ptr::read(ptr::addr_of!(slots) as *const [SingleThreadRefCell<T>; N]) },
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn slot<C: Implies<PreemptDisabled>>(&self, context: &C) -> &SingleThreadRefCell<T> {
+        let cpu = context.as_weaker().cpu();
+        match self.slots.get(cpu) {
+            Some(slot) => slot,
+            None => panic!("PerCpu with {} slots accessed on CPU {}", N, cpu),
+        }
+    }
+
+    /// Immutably borrows the value of the current CPU.
+    ///
+    /// With `Init` as the context, this is CPU 0, which is assumed to be the boot CPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed or if the CPU index is out of range.
+    #[inline]
+    #[track_caller]
+    pub fn borrow<'a, 'b, C: Implies<PreemptDisabled> + 'b>(&'a self, context: &'b C) -> SingleThreadRef<'a, 'b, T, C> {
+        self.slot(context).borrow_in(context)
+    }
+
+    /// Mutably borrows the value of the current CPU.
+    ///
+    /// With `Init` as the context, this is CPU 0, which is assumed to be the boot CPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed or if the CPU index is out of range.
+    #[inline]
+    #[track_caller]
+    pub fn borrow_mut<'a, 'b, C: Implies<PreemptDisabled> + 'b>(&'a self, context: &'b C) -> SingleThreadRefMut<'a, 'b, T, C> {
+        self.slot(context).borrow_mut_in(context)
+    }
+
+    /// Returns the cells of all CPUs, in CPU order. They can only be used while `init` is alive.
+    #[inline]
+    pub fn iter_all<'a: 'b, 'b>(&'a self, _init: &'b Init) -> slice::Iter<'b, SingleThreadRefCell<T>> {
+        self.slots.iter()
+    }
+
+    /// Returns a mutable reference to the value of CPU `cpu`.
+    ///
+    /// No context is needed because the mutable borrow proves exclusive access.
+    #[inline]
+    pub fn get_mut(&mut self, cpu: usize) -> Option<&mut T> {
+        self.slots.get_mut(cpu).map(SingleThreadRefCell::get_mut)
+    }
+}
+
+#[test]
+fn test_percpu() {
+    static VALUES: PerCpu<u32, 2> = PerCpu::new([0, 0]);
+
+    for cpu in 0..2 {
+        MockPreempt::set_cpu(cpu);
+        let guard = PreemptGuard::<MockPreempt>::disable();
+        *VALUES.borrow_mut(guard.context()) += cpu as u32 + 1;
+    }
+
+    let init = unsafe { Init::new() };
+    assert_eq!(*VALUES.borrow(&init), 1);
+    let mut cells = VALUES.iter_all(&init);
+    assert_eq!(*cells.next().unwrap().borrow(&init), 1);
+    assert_eq!(*cells.next().unwrap().borrow(&init), 2);
+    assert!(cells.next().is_none());
+}
+
+#[test]
+#[should_panic(expected = "PerCpu with 0 slots accessed on CPU 0")]
+fn test_percpu_out_of_range() {
+    static VALUES: PerCpu<u32, 0> = PerCpu::new([]);
+
+    let init = unsafe { Init::new() };
+    VALUES.borrow(&init);
+}
@@ -0,0 +1,238 @@
+#[cfg(any(test, feature = "mock"))]
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(test, feature = "mock"))]
+use mutex::RawSpinlock;
+use super::{Implies, Init};
+
+/// Nesting depth of preemption-disabled sections of a thread, maintained by `PreemptGuard`.
+pub struct PreemptCounter {
+    count: AtomicUsize,
+}
+
+impl PreemptCounter {
+    #[inline]
+    pub const fn new() -> PreemptCounter {
+        PreemptCounter {
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of `PreemptGuard`s alive on the thread owning the counter.
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for PreemptCounter {
+    fn default() -> PreemptCounter {
+        PreemptCounter::new()
+    }
+}
+
+/// Architecture or scheduler hooks for disabling preemption, used by `PreemptGuard`.
+///
+/// # Safety
+///
+/// Between `disable` and the matching `enable`, the current thread must not be preempted by
+/// another thread or migrated to another CPU. `counter` must return the same counter for as long
+/// as the current thread runs and a different one for every other thread.
+///
+/// `PreemptGuard`s must not be created in interrupt handlers, since a handler could then access
+/// the per-CPU data of the code it interrupted. A backend for a system with interrupts must rule
+/// this out, for example by panicking in `counter` when called from a handler.
+pub unsafe trait PreemptBackend {
+    /// Returns the preemption counter of the current thread.
+    fn counter() -> &'static PreemptCounter;
+
+    /// Disables preemption. Called when the counter goes from zero to one.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by `PreemptGuard`.
+    unsafe fn disable();
+
+    /// Enables preemption. Called when the counter returns to zero.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by `PreemptGuard`.
+    unsafe fn enable();
+
+    /// Returns the index of the CPU the current thread runs on. Only called while preemption is
+    /// disabled. Uniprocessor backends can keep the default, which always returns 0.
+    #[inline]
+    fn current_cpu() -> usize {
+        0
+    }
+}
+
+/// Context that exists while preemption is disabled.
+///
+/// The current thread keeps running on the same CPU while this is alive, so it may access data
+/// that belongs to that CPU. Interrupt handlers can still run, so this is not an STC, but they
+/// cannot obtain this context themselves (see `PreemptBackend`).
+pub struct PreemptDisabled {
+    cpu: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl PreemptDisabled {
+    /// Returns the index of the CPU the current thread is pinned to.
+    #[inline]
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+}
+
+/// CPU 0 is assumed to be the boot CPU, the only one that runs during `Init`.
+unsafe impl Implies<PreemptDisabled> for Init {
+    #[inline]
+    fn as_weaker(&self) -> &PreemptDisabled {
+        &PreemptDisabled { cpu: 0, _not_send: PhantomData }
+    }
+}
+
+/// Disables preemption on creation and enables it again when the last nested guard is dropped.
+///
+/// Only the outermost guard calls the backend. Nested guards only update the counter.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{PreemptBackend, PreemptCounter, PreemptGuard};
+/// # struct Sched;
+/// # static COUNTER: PreemptCounter = PreemptCounter::new();
+/// # unsafe impl PreemptBackend for Sched {
+/// #     fn counter() -> &'static PreemptCounter { &COUNTER }
+/// #     unsafe fn disable() {}
+/// #     unsafe fn enable() {}
+/// # }
+///
+/// let outer = PreemptGuard::<Sched>::disable();
+/// let inner = PreemptGuard::<Sched>::disable();
+/// assert_eq!(Sched::counter().get(), 2);
+/// drop(inner);
+/// drop(outer);
+/// assert_eq!(Sched::counter().get(), 0);
+/// ```
+pub struct PreemptGuard<B: PreemptBackend> {
+    context: PreemptDisabled,
+    _backend: PhantomData<B>,
+}
+
+impl<B: PreemptBackend> PreemptGuard<B> {
+    /// Disables preemption, or increments the counter if it is already disabled.
+    #[inline]
+    pub fn disable() -> PreemptGuard<B> {
+        let counter = B::counter();
+        let count = counter.get();
+        if count == 0 {
+            unsafe { B::disable() };
+        }
+        counter.count.store(count + 1, Ordering::Relaxed);
+        PreemptGuard {
+            context: PreemptDisabled {
+                cpu: B::current_cpu(),
+                _not_send: PhantomData,
+            },
+            _backend: PhantomData,
+        }
+    }
+
+    /// Returns the context for the section in which preemption is disabled.
+    #[inline]
+    pub fn context(&self) -> &PreemptDisabled {
+        &self.context
+    }
+}
+
+impl<B: PreemptBackend> Drop for PreemptGuard<B> {
+    #[inline]
+    fn drop(&mut self) {
+        let counter = B::counter();
+        let count = counter.get() - 1;
+        counter.count.store(count, Ordering::Relaxed);
+        if count == 0 {
+            unsafe { B::enable() };
+        }
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+static MOCK_PREEMPT_LOCK: RawSpinlock = RawSpinlock::new();
+
+#[cfg(any(test, feature = "mock"))]
+std::thread_local! {
+    // Leaked once per thread, since the backend must return a static counter
+    static MOCK_PREEMPT_COUNTER: &'static PreemptCounter = std::boxed::Box::leak(std::boxed::Box::new(PreemptCounter::new()));
+    static MOCK_PREEMPT_ENABLED: Cell<bool> = const { Cell::new(true) };
+    static MOCK_PREEMPT_CPU: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Software preemption backend for testing on a host.
+///
+/// Each thread has its own counter, preemption flag and CPU index. Disabling preemption takes a
+/// global lock until it is enabled again, so at most one thread at a time runs with preemption
+/// disabled, whatever CPU index it reports. Threads that run tests in parallel only wait for each
+/// other in these sections.
+#[cfg(any(test, feature = "mock"))]
+#[cfg_attr(feature = "nightly", doc(cfg(feature = "mock")))]
+pub struct MockPreempt;
+
+#[cfg(any(test, feature = "mock"))]
+impl MockPreempt {
+    /// Returns whether preemption is currently enabled.
+    pub fn enabled() -> bool {
+        MOCK_PREEMPT_ENABLED.with(Cell::get)
+    }
+
+    /// Sets the CPU index reported to guards created afterwards on the current thread.
+    pub fn set_cpu(cpu: usize) {
+        MOCK_PREEMPT_CPU.with(|current| current.set(cpu));
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+unsafe impl PreemptBackend for MockPreempt {
+    fn counter() -> &'static PreemptCounter {
+        MOCK_PREEMPT_COUNTER.with(|counter| *counter)
+    }
+
+    unsafe fn disable() {
+        MOCK_PREEMPT_LOCK.lock();
+        MOCK_PREEMPT_ENABLED.with(|enabled| enabled.set(false));
+    }
+
+    unsafe fn enable() {
+        MOCK_PREEMPT_ENABLED.with(|enabled| enabled.set(true));
+        MOCK_PREEMPT_LOCK.unlock();
+    }
+
+    fn current_cpu() -> usize {
+        MOCK_PREEMPT_CPU.with(Cell::get)
+    }
+}
+
+#[test]
+fn test_preempt_guard() {
+    assert!(MockPreempt::enabled());
+    {
+        let _outer = PreemptGuard::<MockPreempt>::disable();
+        assert!(!MockPreempt::enabled());
+        {
+            let _inner = PreemptGuard::<MockPreempt>::disable();
+            assert_eq!(MockPreempt::counter().get(), 2);
+        }
+        assert_eq!(MockPreempt::counter().get(), 1);
+        assert!(!MockPreempt::enabled());
+        assert!(std::thread::spawn(MockPreempt::enabled).join().unwrap());
+    }
+    assert_eq!(MockPreempt::counter().get(), 0);
+    assert!(MockPreempt::enabled());
+
+    MockPreempt::set_cpu(3);
+    assert_eq!(PreemptGuard::<MockPreempt>::disable().context().cpu(), 3);
+    MockPreempt::set_cpu(0);
+}
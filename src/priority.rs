@@ -0,0 +1,261 @@
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+#[cfg(any(test, feature = "mock"))]
+use core::sync::atomic::{AtomicU8, Ordering};
+use super::Init;
+
+/// Hooks for the interrupt priority mask, used by `PriorityCell::lock`.
+///
+/// Priorities are logical: a higher number is more urgent, whatever the encoding of the hardware.
+///
+/// # Safety
+///
+/// After `raise(ceiling)` and until the matching `restore`, no interrupt handler with a priority
+/// of `ceiling` or lower may run.
+pub unsafe trait PriorityMask {
+    /// Saved mask, typically the previous value of the mask register.
+    type State: Copy;
+
+    /// Masks all interrupts with a priority of `ceiling` or lower, if not already masked, and
+    /// returns the previous mask.
+    fn raise(ceiling: u8) -> Self::State;
+
+    /// Restores the mask returned by `raise`.
+    ///
+    /// # Safety
+    ///
+    /// Must be called at most once for each state, in reverse order of `raise`.
+    unsafe fn restore(state: Self::State);
+}
+
+/// Context of code that runs at priority `P`, or with the mask raised to `P`.
+///
+/// No other code that runs at priority `P` or lower can preempt the holder. `M` is the mask
+/// backend used to raise the priority further. It is part of the context type so that `lock` can
+/// raise the mask without naming the backend at every call. Firmware for a single target can name
+/// it once with an alias:
+///
+/// ```
+/// # use concurrency_context::PriorityMask;
+/// # struct Basepri;
+/// # unsafe impl PriorityMask for Basepri {
+/// #     type State = ();
+/// #     fn raise(_ceiling: u8) {}
+/// #     unsafe fn restore(_state: ()) {}
+/// # }
+/// type Priority<const P: u8> = concurrency_context::Priority<Basepri, P>;
+///
+/// let task: Priority<1> = unsafe { Priority::new() };
+/// ```
+pub struct Priority<M: PriorityMask, const P: u8> {
+    _mask: PhantomData<M>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<M: PriorityMask, const P: u8> Priority<M, P> {
+    /// Creates the context, typically at the start of a task or interrupt handler.
+    ///
+    /// # Safety
+    ///
+    /// The caller must run at priority `P` or with the mask raised to at least `P`, for as long as
+    /// the context exists. No other `Priority` or `Init` may exist in the same task or handler.
+    #[inline]
+    pub unsafe fn new() -> Priority<M, P> {
+        Priority {
+            _mask: PhantomData,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// Implemented by contexts whose priority is `P` or higher.
+///
+/// `Init` has every priority because no interrupt handler runs yet. The table covers the
+/// priorities 0 to 15.
+///
+/// # Safety
+///
+/// Must only be implemented for contexts that no code of priority `P` or lower can preempt.
+pub unsafe trait AtLeast<const P: u8> {}
+
+unsafe impl<const P: u8> AtLeast<P> for Init {}
+
+macro_rules! impl_at_least {
+    ($low:tt $($high:tt)*) => {
+        unsafe impl<M: PriorityMask> AtLeast<$low> for Priority<M, $low> {}
+        $(unsafe impl<M: PriorityMask> AtLeast<$low> for Priority<M, $high> {})*
+        impl_at_least!($($high)*);
+    };
+    () => {};
+}
+
+impl_at_least!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
+struct MaskGuard<M: PriorityMask> {
+    state: M::State,
+}
+
+impl<M: PriorityMask> Drop for MaskGuard<M> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { M::restore(self.state) }
+    }
+}
+
+/// A resource shared between tasks and interrupt handlers using the priority ceiling protocol.
+///
+/// `CEILING` is the highest priority of any task or handler that accesses the cell. Contexts of at
+/// least that priority borrow the value directly, since nothing else that accesses it can preempt
+/// them. Lower priorities go through `lock`, which raises the mask to the ceiling for the duration
+/// of a closure. Like `TokenCell`, exclusivity is checked at compile time through the borrow of
+/// the context.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{Priority, PriorityCell, PriorityMask};
+/// # struct Basepri;
+/// # unsafe impl PriorityMask for Basepri {
+/// #     type State = ();
+/// #     fn raise(_ceiling: u8) {}
+/// #     unsafe fn restore(_state: ()) {}
+/// # }
+/// static SAMPLES: PriorityCell<u32, 2> = unsafe { PriorityCell::new(0) };
+///
+/// // Interrupt handler at priority 2
+/// let mut adc = unsafe { Priority::<Basepri, 2>::new() };
+/// *SAMPLES.borrow_mut(&mut adc) += 1;
+/// drop(adc);
+///
+/// // Task at priority 1
+/// let mut task = unsafe { Priority::<Basepri, 1>::new() };
+/// let samples = SAMPLES.lock(&mut task, |ctx| *SAMPLES.borrow(ctx));
+/// assert_eq!(samples, 1);
+/// ```
+///
+/// A lower priority cannot borrow the value without locking:
+/// ```compile_fail
+/// use concurrency_context::{Priority, PriorityCell, PriorityMask};
+/// # struct Basepri;
+/// # unsafe impl PriorityMask for Basepri {
+/// #     type State = ();
+/// #     fn raise(_ceiling: u8) {}
+/// #     unsafe fn restore(_state: ()) {}
+/// # }
+/// static SAMPLES: PriorityCell<u32, 2> = unsafe { PriorityCell::new(0) };
+///
+/// let mut task = unsafe { Priority::<Basepri, 1>::new() };
+/// *SAMPLES.borrow_mut(&mut task) += 1;
+/// ```
+///
+/// Ceilings above 15 are rejected:
+/// ```compile_fail
+/// use concurrency_context::PriorityCell;
+/// static TOO_HIGH: PriorityCell<u32, 16> = unsafe { PriorityCell::new(0) };
+/// ```
+pub struct PriorityCell<T, const CEILING: u8> {
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send, const CEILING: u8> Sync for PriorityCell<T, CEILING> {}
+
+impl<T, const CEILING: u8> PriorityCell<T, CEILING> {
+    /// Creates the cell.
+    ///
+    /// # Safety
+    ///
+    /// No task or interrupt handler with a priority above `CEILING` may access the cell.
+    ///
+    /// `CEILING` must be at most 15, otherwise this fails to compile.
+    #[inline]
+    pub const unsafe fn new(value: T) -> PriorityCell<T, CEILING> {
+        const { assert!(CEILING <= 15, "PriorityCell ceiling must be at most 15") };
+        PriorityCell {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    #[inline]
+    pub fn borrow<'a, C: AtLeast<CEILING>>(&'a self, _context: &'a C) -> &'a T {
+        unsafe { &*self.value.get() }
+    }
+
+    #[inline]
+    pub fn borrow_mut<'a, C: AtLeast<CEILING>>(&'a self, _context: &'a mut C) -> &'a mut T {
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Runs `f` with a context at the ceiling priority, raising the mask to the ceiling first if
+    /// the priority of `context` is lower.
+    #[inline]
+    pub fn lock<M: PriorityMask, const P: u8, R, F: FnOnce(&mut Priority<M, CEILING>) -> R>(&self, _context: &mut Priority<M, P>, f: F) -> R {
+        let _mask = if P < CEILING {
+            Some(MaskGuard::<M> { state: M::raise(CEILING) })
+        } else {
+            None
+        };
+        f(&mut Priority {
+            _mask: PhantomData,
+            _not_send: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+static MOCK_PRIORITY_MASK: AtomicU8 = AtomicU8::new(0);
+
+/// Software priority mask backend for testing on a host.
+///
+/// This only tracks a global mask value and does not mask anything.
+#[cfg(any(test, feature = "mock"))]
+#[cfg_attr(feature = "nightly", doc(cfg(feature = "mock")))]
+pub struct MockPriorityMask;
+
+#[cfg(any(test, feature = "mock"))]
+impl MockPriorityMask {
+    /// Returns the current mask. Interrupts with this priority or lower are masked.
+    pub fn mask() -> u8 {
+        MOCK_PRIORITY_MASK.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+unsafe impl PriorityMask for MockPriorityMask {
+    type State = u8;
+
+    fn raise(ceiling: u8) -> u8 {
+        MOCK_PRIORITY_MASK.fetch_max(ceiling, Ordering::SeqCst)
+    }
+
+    unsafe fn restore(state: u8) {
+        MOCK_PRIORITY_MASK.store(state, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_priority_cell() {
+    static G_INT: PriorityCell<i32, 2> = unsafe { PriorityCell::new(5) };
+
+    let mut task = unsafe { Priority::<MockPriorityMask, 1>::new() };
+    G_INT.lock(&mut task, |ctx| {
+        assert_eq!(MockPriorityMask::mask(), 2);
+        *G_INT.borrow_mut(ctx) += 1;
+    });
+    assert_eq!(MockPriorityMask::mask(), 0);
+
+    let mut handler = unsafe { Priority::<MockPriorityMask, 2>::new() };
+    *G_INT.borrow_mut(&mut handler) += 1;
+    G_INT.lock(&mut handler, |ctx| {
+        assert_eq!(MockPriorityMask::mask(), 0);
+        assert_eq!(*G_INT.borrow(ctx), 7);
+    });
+}
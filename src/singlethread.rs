@@ -1,7 +1,22 @@
+use core::cell::{Cell, UnsafeCell};
+#[cfg(not(all(concurrency_context_unchecked, not(debug_assertions))))]
 use core::cell::{Ref, RefCell, RefMut};
+// Drop-in replacements that do no borrow tracking
+#[cfg(all(concurrency_context_unchecked, not(debug_assertions)))]
+use unchecked::{Ref, RefCell, RefMut};
+use core::fmt;
 use core::marker::PhantomData;
+use core::mem;
 use core::ops::{Deref, DerefMut};
-use super::STC;
+#[cfg(feature = "track-borrows")]
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+#[cfg(feature = "std")]
+use std::thread::{self, ThreadId};
+use super::{concurrent_started, Concurrent, ExclusiveSTC, STC};
 
 /// A context-aware wrapper around RefCell that can be accessed in single-thread context.
 ///
@@ -17,9 +32,20 @@ use super::STC;
 /// unsafe code for each mutable static accesses. This will catch data races that would be caused,
 /// for example, by starting a thread earlier in the program
 ///
+/// With the `std` feature, the cell records the thread that first borrows it and panics if it is
+/// later borrowed from any other thread. Borrows made with contexts that are not bound to a thread,
+/// such as `CsContext`, are not checked. With the `track-borrows` feature, the cell remembers where
+/// it was last borrowed, and a panic caused by a conflicting borrow names both the conflicting call
+/// and that site. When shared borrows overlap, this is the most recent of them, which may already
+/// have been released while an earlier one is still outstanding. Without either feature, the only state added to the
+/// underlying RefCell is a flag recording whether the value was frozen with `freeze`.
+///
+/// With the `concurrency_context_unchecked` cfg, release builds replace the RefCell with an
+/// UnsafeCell and the guards with plain references. The API is unchanged, but conflicting borrows are no longer
+/// detected and are undefined behavior. Debug builds keep the checks.
+///
 /// # Example
 /// ```
-/// #![feature(const_fn)]
 /// use concurrency_context::SingleThreadRefCell;
 /// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
 ///
@@ -40,17 +66,26 @@ use super::STC;
 /// }
 /// ```
 pub struct SingleThreadRefCell<T> {
-    value: RefCell<T>
+    value: RefCell<T>,
+    frozen: AtomicBool,
+    #[cfg(feature = "std")]
+    owner: OnceLock<ThreadId>,
+    #[cfg(feature = "track-borrows")]
+    borrowed_at: Cell<Option<&'static Location<'static>>>,
 }
 
 unsafe impl<T> Sync for SingleThreadRefCell<T> {}
 
-pub struct SingleThreadRef<'a, 'b, T: 'a, C: STC + 'b> {
+/// Whether conflicting borrows of a `SingleThreadRefCell` are detected. `false` only in release
+/// builds with the `concurrency_context_unchecked` cfg.
+pub const BORROWS_CHECKED: bool = !cfg!(all(concurrency_context_unchecked, not(debug_assertions)));
+
+pub struct SingleThreadRef<'a, 'b, T: ?Sized + 'a, C: 'b> {
     value: Ref<'a, T>,
     _context: PhantomData<&'b C>,
 }
 
-impl<'a, 'b, T: 'a, C: STC + 'b> Deref for SingleThreadRef<'a, 'b, T, C> {
+impl<'a, 'b, T: ?Sized + 'a, C: 'b> Deref for SingleThreadRef<'a, 'b, T, C> {
     type Target = T;
 
     #[inline]
@@ -59,12 +94,12 @@ impl<'a, 'b, T: 'a, C: STC + 'b> Deref for SingleThreadRef<'a, 'b, T, C> {
     }
 }
 
-pub struct SingleThreadRefMut<'a, 'b, T: 'a, C: STC + 'b> {
+pub struct SingleThreadRefMut<'a, 'b, T: ?Sized + 'a, C: 'b> {
     value: RefMut<'a, T>,
     _context: PhantomData<&'b C>,
 }
 
-impl<'a, 'b, T: 'a, C: STC + 'b> Deref for SingleThreadRefMut<'a, 'b, T, C> {
+impl<'a, 'b, T: ?Sized + 'a, C: 'b> Deref for SingleThreadRefMut<'a, 'b, T, C> {
     type Target = T;
 
     #[inline]
@@ -73,46 +108,964 @@ impl<'a, 'b, T: 'a, C: STC + 'b> Deref for SingleThreadRefMut<'a, 'b, T, C> {
     }
 }
 
-impl<'a, 'b, T: 'a, C: STC + 'b> DerefMut for SingleThreadRefMut<'a, 'b, T, C> {
+impl<'a, 'b, T: ?Sized + 'a, C: 'b> DerefMut for SingleThreadRefMut<'a, 'b, T, C> {
     #[inline]
     fn deref_mut(&mut self) -> &mut T {
         self.value.deref_mut()
     }
 }
 
+impl<'a, 'b, T: ?Sized + fmt::Debug + 'a, C: 'b> fmt::Debug for SingleThreadRef<'a, 'b, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, 'b, T: ?Sized + fmt::Display + 'a, C: 'b> fmt::Display for SingleThreadRef<'a, 'b, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, 'b, T: ?Sized + fmt::Debug + 'a, C: 'b> fmt::Debug for SingleThreadRefMut<'a, 'b, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, 'b, T: ?Sized + fmt::Display + 'a, C: 'b> fmt::Display for SingleThreadRefMut<'a, 'b, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, 'b, T: ?Sized + 'a, C: 'b> SingleThreadRef<'a, 'b, T, C> {
+    /// Makes a new `SingleThreadRef` for a component of the borrowed data.
+    ///
+    /// This is an associated function like `Ref::map` so it does not shadow methods of `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{SingleThreadRef, SingleThreadRefCell};
+    /// static G_PAIR: SingleThreadRefCell<(i32, char)> = SingleThreadRefCell::new((5, 'b'));
+    ///
+    /// let ctx = unsafe { concurrency_context::Init::new() };
+    /// let g = SingleThreadRef::map(G_PAIR.borrow(&ctx), |pair| &pair.1);
+    /// assert_eq!(*g, 'b');
+    /// ```
+    #[inline]
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(orig: Self, f: F) -> SingleThreadRef<'a, 'b, U, C> {
+        SingleThreadRef {
+            value: Ref::map(orig.value, f),
+            _context: PhantomData,
+        }
+    }
+
+    /// Makes a new `SingleThreadRef` for an optional component of the borrowed data. The original
+    /// guard is returned as `Err` if the closure returns `None`.
+    #[inline]
+    pub fn filter_map<U: ?Sized, F: FnOnce(&T) -> Option<&U>>(orig: Self, f: F) -> Result<SingleThreadRef<'a, 'b, U, C>, Self> {
+        match Ref::filter_map(orig.value, f) {
+            Ok(value) => Ok(SingleThreadRef {
+                value,
+                _context: PhantomData,
+            }),
+            Err(value) => Err(SingleThreadRef {
+                value,
+                _context: PhantomData,
+            }),
+        }
+    }
+
+    /// Splits a `SingleThreadRef` into multiple guards for different components of the borrowed
+    /// data.
+    #[inline]
+    pub fn map_split<U: ?Sized, V: ?Sized, F: FnOnce(&T) -> (&U, &V)>(orig: Self, f: F) -> (SingleThreadRef<'a, 'b, U, C>, SingleThreadRef<'a, 'b, V, C>) {
+        let (a, b) = Ref::map_split(orig.value, f);
+        (SingleThreadRef {
+            value: a,
+            _context: PhantomData,
+        }, SingleThreadRef {
+            value: b,
+            _context: PhantomData,
+        })
+    }
+}
+
+impl<'a, 'b, T: ?Sized + 'a, C: 'b> SingleThreadRefMut<'a, 'b, T, C> {
+    /// Makes a new `SingleThreadRefMut` for a component of the borrowed data.
+    ///
+    /// This is an associated function like `RefMut::map` so it does not shadow methods of `T`.
+    #[inline]
+    pub fn map<U: ?Sized, F: FnOnce(&mut T) -> &mut U>(orig: Self, f: F) -> SingleThreadRefMut<'a, 'b, U, C> {
+        SingleThreadRefMut {
+            value: RefMut::map(orig.value, f),
+            _context: PhantomData,
+        }
+    }
+
+    /// Makes a new `SingleThreadRefMut` for an optional component of the borrowed data. The
+    /// original guard is returned as `Err` if the closure returns `None`.
+    #[inline]
+    pub fn filter_map<U: ?Sized, F: FnOnce(&mut T) -> Option<&mut U>>(orig: Self, f: F) -> Result<SingleThreadRefMut<'a, 'b, U, C>, Self> {
+        match RefMut::filter_map(orig.value, f) {
+            Ok(value) => Ok(SingleThreadRefMut {
+                value,
+                _context: PhantomData,
+            }),
+            Err(value) => Err(SingleThreadRefMut {
+                value,
+                _context: PhantomData,
+            }),
+        }
+    }
+
+    /// Splits a `SingleThreadRefMut` into multiple guards for different components of the
+    /// borrowed data.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{SingleThreadRefCell, SingleThreadRefMut};
+    /// static G_ARRAY: SingleThreadRefCell<[i32; 4]> = SingleThreadRefCell::new([1, 2, 3, 4]);
+    ///
+    /// let ctx = unsafe { concurrency_context::Init::new() };
+    /// {
+    ///     let g = G_ARRAY.borrow_mut(&ctx);
+    ///     let (mut lo, mut hi) = SingleThreadRefMut::map_split(g, |a| a.split_at_mut(2));
+    ///     lo.swap_with_slice(&mut hi);
+    /// }
+    /// assert_eq!(*G_ARRAY.borrow(&ctx), [3, 4, 1, 2]);
+    /// ```
+    #[inline]
+    pub fn map_split<U: ?Sized, V: ?Sized, F: FnOnce(&mut T) -> (&mut U, &mut V)>(orig: Self, f: F) -> (SingleThreadRefMut<'a, 'b, U, C>, SingleThreadRefMut<'a, 'b, V, C>) {
+        let (a, b) = RefMut::map_split(orig.value, f);
+        (SingleThreadRefMut {
+            value: a,
+            _context: PhantomData,
+        }, SingleThreadRefMut {
+            value: b,
+            _context: PhantomData,
+        })
+    }
+}
+
 impl<T> SingleThreadRefCell<T> {
     #[inline]
     pub const fn new(value: T) -> SingleThreadRefCell<T> {
         SingleThreadRefCell {
-            value: RefCell::new(value)
+            value: RefCell::new(value),
+            frozen: AtomicBool::new(false),
+            #[cfg(feature = "std")]
+            owner: OnceLock::new(),
+            #[cfg(feature = "track-borrows")]
+            borrowed_at: Cell::new(None),
         }
     }
 
+    #[cfg(feature = "std")]
     #[inline]
-    pub fn borrow<'a, 'b, C: STC + 'b>(&'a self, _context: &'b C) -> SingleThreadRef<'a, 'b, T, C> {
-        SingleThreadRef {
-            value: self.value.borrow(),
-            _context: PhantomData,
+    #[track_caller]
+    fn check_owner<C: STC>(&self, context: &C) {
+        context.check_thread();
+        if !C::THREAD_BOUND {
+            return;
+        }
+        let current = thread::current().id();
+        let owner = *self.owner.get_or_init(|| current);
+        if owner != current {
+            panic!("SingleThreadRefCell owned by thread {:?} borrowed on thread {:?}", owner, current);
         }
     }
 
+    #[cfg(not(feature = "std"))]
     #[inline]
-    pub fn borrow_mut<'a, 'b, C: STC + 'b>(&'a self, _context: &'b C) -> SingleThreadRefMut<'a, 'b, T, C> {
-        SingleThreadRefMut {
-            value: self.value.borrow_mut(),
+    #[track_caller]
+    fn check_owner<C: STC>(&self, context: &C) {
+        context.check_thread();
+    }
+
+    /// Records the caller as the site of the borrow that was just made.
+    #[cfg(feature = "track-borrows")]
+    #[inline]
+    #[track_caller]
+    fn record_borrow_site(&self) {
+        self.borrowed_at.set(Some(Location::caller()));
+    }
+
+    #[cfg(not(feature = "track-borrows"))]
+    #[inline]
+    fn record_borrow_site(&self) {}
+
+    #[cfg(feature = "track-borrows")]
+    #[cold]
+    #[track_caller]
+    fn borrow_failed<E: fmt::Display>(&self, err: E) -> ! {
+        match self.borrowed_at.get() {
+            Some(site) => panic!("{} at {}; last borrowed at {}", err, Location::caller(), site),
+            None => panic!("{} at {}", err, Location::caller()),
+        }
+    }
+
+    #[cfg(not(feature = "track-borrows"))]
+    #[cold]
+    #[track_caller]
+    fn borrow_failed<E: fmt::Display>(&self, err: E) -> ! {
+        panic!("{}", err)
+    }
+
+    /// Immutably borrows the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn borrow<'a, 'b, C: STC + 'b>(&'a self, context: &'b C) -> SingleThreadRef<'a, 'b, T, C> {
+        match self.try_borrow(context) {
+            Ok(borrow) => borrow,
+            Err(err) => self.borrow_failed(err),
+        }
+    }
+
+    /// Mutably borrows the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn borrow_mut<'a, 'b, C: STC + 'b>(&'a self, context: &'b C) -> SingleThreadRefMut<'a, 'b, T, C> {
+        match self.try_borrow_mut(context) {
+            Ok(borrow) => borrow,
+            Err(err) => self.borrow_failed(err),
+        }
+    }
+
+    /// Immutably borrows the value, returning an error if it is currently mutably borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn try_borrow<'a, 'b, C: STC + 'b>(&'a self, context: &'b C) -> Result<SingleThreadRef<'a, 'b, T, C>, BorrowError> {
+        self.check_owner(context);
+        self.try_borrow_in(context)
+    }
+
+    /// Mutably borrows the value, returning an error if it is currently borrowed.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{BorrowKind, SingleThreadRefCell};
+    /// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+    /// # if !concurrency_context::BORROWS_CHECKED { return; }
+    ///
+    /// let ctx = unsafe { concurrency_context::Init::new() };
+    /// let g = G_INT.borrow(&ctx);
+    /// let err = G_INT.try_borrow_mut(&ctx).err().unwrap();
+    /// assert_eq!(err.outstanding(), BorrowKind::Shared);
+    /// drop(g);
+    /// assert!(G_INT.try_borrow_mut(&ctx).is_ok());
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn try_borrow_mut<'a, 'b, C: STC + 'b>(&'a self, context: &'b C) -> Result<SingleThreadRefMut<'a, 'b, T, C>, BorrowMutError> {
+        self.check_owner(context);
+        self.try_borrow_mut_in(context)
+    }
+
+    /// Like `borrow`, but for any context type and without the owner thread check.
+    ///
+    /// For containers of this crate whose own contexts already prove that the cell is not accessed
+    /// concurrently, such as `PerCpu`.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn borrow_in<'a, 'b, C: 'b>(&'a self, context: &'b C) -> SingleThreadRef<'a, 'b, T, C> {
+        match self.try_borrow_in(context) {
+            Ok(borrow) => borrow,
+            Err(err) => self.borrow_failed(err),
+        }
+    }
+
+    /// Like `borrow_mut`, but for any context type and without the owner thread check.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn borrow_mut_in<'a, 'b, C: 'b>(&'a self, context: &'b C) -> SingleThreadRefMut<'a, 'b, T, C> {
+        match self.try_borrow_mut_in(context) {
+            Ok(borrow) => borrow,
+            Err(err) => self.borrow_failed(err),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    pub(crate) fn try_borrow_in<'a, 'b, C: 'b>(&'a self, _context: &'b C) -> Result<SingleThreadRef<'a, 'b, T, C>, BorrowError> {
+        match self.value.try_borrow() {
+            Ok(value) => {
+                self.record_borrow_site();
+                Ok(SingleThreadRef {
+                    value,
+                    _context: PhantomData,
+                })
+            }
+            Err(_) => Err(BorrowError { _private: () }),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    pub(crate) fn try_borrow_mut_in<'a, 'b, C: 'b>(&'a self, _context: &'b C) -> Result<SingleThreadRefMut<'a, 'b, T, C>, BorrowMutError> {
+        match self.value.try_borrow_mut() {
+            Ok(value) => {
+                self.record_borrow_site();
+                Ok(SingleThreadRefMut {
+                    value,
+                    _context: PhantomData,
+                })
+            }
+            Err(_) => {
+                let outstanding = if self.value.try_borrow().is_ok() {
+                    BorrowKind::Shared
+                } else {
+                    BorrowKind::Mutable
+                };
+                Err(BorrowMutError { outstanding })
+            }
+        }
+    }
+
+    /// Replaces the value, returning the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn replace<C: STC>(&self, context: &C, value: T) -> T {
+        mem::replace(&mut *self.borrow_mut(context), value)
+    }
+
+    /// Replaces the value with one computed from the old one by `f`, returning the old value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn replace_with<C: STC, F: FnOnce(&mut T) -> T>(&self, context: &C, f: F) -> T {
+        let mut value = self.borrow_mut(context);
+        let new = f(&mut value);
+        mem::replace(&mut *value, new)
+    }
+
+    /// Swaps the values of two cells.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value in either cell is currently borrowed.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::SingleThreadRefCell;
+    /// static G_FRONT: SingleThreadRefCell<[u8; 2]> = SingleThreadRefCell::new([1, 1]);
+    /// static G_BACK: SingleThreadRefCell<[u8; 2]> = SingleThreadRefCell::new([2, 2]);
+    ///
+    /// let ctx = unsafe { concurrency_context::Init::new() };
+    /// G_FRONT.swap(&ctx, &G_BACK);
+    /// assert_eq!(*G_FRONT.borrow(&ctx), [2, 2]);
+    /// assert_eq!(G_BACK.replace(&ctx, [3, 3]), [1, 1]);
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn swap<C: STC>(&self, context: &C, other: &SingleThreadRefCell<T>) {
+        mem::swap(&mut *self.borrow_mut(context), &mut *other.borrow_mut(context))
+    }
+
+    /// Consumes the cell, returning the value.
+    ///
+    /// No context is needed because owning the cell means nothing else can access it.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the value.
+    ///
+    /// No context is needed because the mutable borrow of the cell guarantees exclusive access.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Sync> SingleThreadRefCell<T> {
+    /// Permanently freezes the value, returning a reference to it that is no longer tied to the
+    /// context.
+    ///
+    /// This is meant for data that is written while the context is alive and only read once it
+    /// is retired. The returned reference has the lifetime of the cell, so for a static it can be
+    /// shared with every thread once concurrency has started. The cell stays immutably borrowed
+    /// forever, so it can still be borrowed immutably with a context but any later attempt to
+    /// borrow it mutably panics, unless borrow tracking is disabled in an unchecked build. Other
+    /// threads, which have no reference to the value yet, can get one with `get_frozen`.
+    ///
+    /// The context is only borrowed rather than consumed: freezing does not end the single-thread
+    /// phase, and a context typically freezes several cells before it is retired. The returned
+    /// reference is valid on other threads either way, since the value can no longer change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{BorrowKind, Init, SingleThreadRefCell};
+    /// static G_TABLE: SingleThreadRefCell<[u8; 4]> = SingleThreadRefCell::new([0; 4]);
+    ///
+    /// let ctx = unsafe { Init::new() };
+    /// G_TABLE.borrow_mut(&ctx)[1] = 1;
+    /// assert!(G_TABLE.get_frozen().is_none());
+    /// let table: &'static [u8; 4] = G_TABLE.freeze(&ctx);
+    /// if concurrency_context::BORROWS_CHECKED {
+    ///     assert_eq!(G_TABLE.try_borrow_mut(&ctx).err().unwrap().outstanding(), BorrowKind::Shared);
+    /// }
+    /// drop(ctx);
+    ///
+    /// std::thread::spawn(move || assert_eq!(table[1], 1)).join().unwrap();
+    /// std::thread::spawn(|| assert_eq!(G_TABLE.get_frozen().unwrap()[1], 1)).join().unwrap();
+    /// ```
+    ///
+    /// Only values that are `Sync` can be frozen, since the reference may be used on any thread:
+    /// ```compile_fail
+    /// use concurrency_context::{Init, SingleThreadRefCell};
+    /// use std::cell::Cell;
+    /// static G_INT: SingleThreadRefCell<Cell<u32>> = SingleThreadRefCell::new(Cell::new(0));
+    ///
+    /// let ctx = unsafe { Init::new() };
+    /// let frozen: &'static Cell<u32> = G_INT.freeze(&ctx);
+    /// ```
+    #[track_caller]
+    #[allow(clippy::forget_non_drop)] // The guard only has drop glue when borrows are tracked
+    pub fn freeze<'a, C: STC>(&'a self, context: &C) -> &'a T {
+        let value = self.borrow(context);
+        // Leaking the borrow keeps the cell immutably borrowed for the rest of its lifetime
+        let frozen = unsafe { &*(&*value as *const T) };
+        mem::forget(value);
+        self.frozen.store(true, Ordering::Release);
+        frozen
+    }
+
+    /// Returns a reference to the value if it was frozen with `freeze`, or `None` otherwise.
+    ///
+    /// No context is needed, since a frozen value never changes and can be read from any thread.
+    #[inline]
+    pub fn get_frozen(&self) -> Option<&T> {
+        if self.frozen.load(Ordering::Acquire) {
+            // The leaked borrow rules out any mutable borrow from now on
+            Some(unsafe { &*self.value.as_ptr() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Default> SingleThreadRefCell<T> {
+    /// Takes the value, leaving `Default::default()` in its place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn take<C: STC>(&self, context: &C) -> T {
+        mem::take(&mut *self.borrow_mut(context))
+    }
+}
+
+impl<T: fmt::Debug> SingleThreadRefCell<T> {
+    /// Returns a value that formats the cell like `RefCell`, showing `<borrowed>` while it is
+    /// mutably borrowed.
+    ///
+    /// The value stays immutably borrowed until the result is dropped. Use this instead of the
+    /// `Debug` implementation of the cell, which has no context and does not show the value.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::SingleThreadRefCell;
+    /// static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+    ///
+    /// let ctx = unsafe { concurrency_context::Init::new() };
+    /// assert_eq!(format!("{:?}", G_INT.debug(&ctx)), "SingleThreadRefCell { value: 5 }");
+    /// assert_eq!(format!("{:?}", G_INT), "SingleThreadRefCell { .. }");
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn debug<'a, C: STC>(&'a self, context: &'a C) -> impl fmt::Debug + 'a {
+        DebugCell {
+            value: self.try_borrow(context),
+        }
+    }
+}
+
+struct DebugCell<'a, T: 'a, C: 'a> {
+    value: Result<SingleThreadRef<'a, 'a, T, C>, BorrowError>,
+}
+
+impl<'a, T: fmt::Debug, C> fmt::Debug for DebugCell<'a, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Ok(ref value) => f.debug_struct("SingleThreadRefCell").field("value", &**value).finish(),
+            Err(_) => f.debug_struct("SingleThreadRefCell").field("value", &format_args!("<borrowed>")).finish(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for SingleThreadRefCell<T> {
+    /// Formats the cell without its value, which cannot be read without a context.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SingleThreadRefCell").finish_non_exhaustive()
+    }
+}
+
+impl<T: Default> Default for SingleThreadRefCell<T> {
+    fn default() -> SingleThreadRefCell<T> {
+        SingleThreadRefCell::new(T::default())
+    }
+}
+
+/// The kind of borrow that prevented a new borrow of a `SingleThreadRefCell`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorrowKind {
+    Shared,
+    Mutable,
+}
+
+/// Error returned by `SingleThreadRefCell::try_borrow`.
+///
+/// An immutable borrow can only fail because the value is mutably borrowed.
+#[derive(Debug)]
+pub struct BorrowError {
+    _private: (),
+}
+
+impl BorrowError {
+    /// Returns the kind of borrow that was outstanding, which is always `BorrowKind::Mutable`.
+    pub fn outstanding(&self) -> BorrowKind {
+        BorrowKind::Mutable
+    }
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("already mutably borrowed")
+    }
+}
+
+/// Error returned by `SingleThreadRefCell::try_borrow_mut`.
+#[derive(Debug)]
+pub struct BorrowMutError {
+    outstanding: BorrowKind,
+}
+
+impl BorrowMutError {
+    /// Returns the kind of borrow that was outstanding.
+    pub fn outstanding(&self) -> BorrowKind {
+        self.outstanding
+    }
+}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outstanding {
+            BorrowKind::Shared => f.write_str("already immutably borrowed"),
+            BorrowKind::Mutable => f.write_str("already mutably borrowed"),
+        }
+    }
+}
+
+/// A context-aware wrapper around Cell that can be accessed in single-thread context.
+///
+/// This is the `Cell` counterpart to `SingleThreadRefCell`. Values are copied or moved in and out
+/// of the cell instead of being borrowed, so no borrow flag is needed and no guard is returned.
+/// Every access requires a borrow on a type implementing STC (single-thread context).
+///
+/// # Example
+/// ```
+/// use concurrency_context::SingleThreadCell;
+/// static G_COUNT: SingleThreadCell<u32> = SingleThreadCell::new(0);
+///
+/// let ctx = unsafe { concurrency_context::Init::new() };
+/// G_COUNT.set(&ctx, 5);
+/// assert_eq!(G_COUNT.update(&ctx, |x| x + 1), 6);
+/// assert_eq!(G_COUNT.replace(&ctx, 1), 6);
+/// assert_eq!(G_COUNT.take(&ctx), 1);
+/// assert_eq!(G_COUNT.get(&ctx), 0);
+/// ```
+pub struct SingleThreadCell<T> {
+    value: Cell<T>
+}
+
+unsafe impl<T> Sync for SingleThreadCell<T> {}
+
+impl<T> SingleThreadCell<T> {
+    #[inline]
+    pub const fn new(value: T) -> SingleThreadCell<T> {
+        SingleThreadCell {
+            value: Cell::new(value)
+        }
+    }
+
+    #[inline]
+    pub fn set<C: STC>(&self, _context: &C, value: T) {
+        self.value.set(value)
+    }
+
+    #[inline]
+    pub fn replace<C: STC>(&self, _context: &C, value: T) -> T {
+        self.value.replace(value)
+    }
+
+    /// Swaps the values of two cells.
+    #[inline]
+    pub fn swap<C: STC>(&self, _context: &C, other: &SingleThreadCell<T>) {
+        self.value.swap(&other.value)
+    }
+}
+
+impl<T: Copy> SingleThreadCell<T> {
+    #[inline]
+    pub fn get<C: STC>(&self, _context: &C) -> T {
+        self.value.get()
+    }
+
+    /// Applies `f` to the contained value, stores the result and returns it.
+    #[inline]
+    pub fn update<C: STC, F: FnOnce(T) -> T>(&self, _context: &C, f: F) -> T {
+        let new = f(self.value.get());
+        self.value.set(new);
+        new
+    }
+}
+
+impl<T: Default> SingleThreadCell<T> {
+    #[inline]
+    pub fn take<C: STC>(&self, _context: &C) -> T {
+        self.value.take()
+    }
+}
+
+/// A cell that can be written to once in single-thread context.
+///
+/// This is intended for static data that is filled in once, typically during `Init`, and only
+/// read afterwards. Writing and reading the cell requires a borrow on a type implementing STC, but
+/// the returned reference is tied to the lifetime of the cell rather than the context, so it can
+/// be handed around freely once initialized. Because the value is never modified or dropped after
+/// it is set, such a reference cannot be invalidated.
+///
+/// # Example
+/// ```
+/// use concurrency_context::SingleThreadOnceCell;
+/// static G_NAME: SingleThreadOnceCell<&'static str> = SingleThreadOnceCell::new();
+///
+/// let ctx = unsafe { concurrency_context::Init::new() };
+/// assert!(G_NAME.get(&ctx).is_none());
+/// assert_eq!(*G_NAME.get_or_init(&ctx, || "boot"), "boot");
+/// assert_eq!(G_NAME.set(&ctx, "again"), Err("again"));
+///
+/// let name: &'static str = G_NAME.get(&ctx).unwrap();
+/// assert_eq!(name, "boot");
+/// ```
+pub struct SingleThreadOnceCell<T> {
+    value: UnsafeCell<Option<T>>
+}
+
+unsafe impl<T: Send + Sync> Sync for SingleThreadOnceCell<T> {}
+
+impl<T> SingleThreadOnceCell<T> {
+    #[inline]
+    pub const fn new() -> SingleThreadOnceCell<T> {
+        SingleThreadOnceCell {
+            value: UnsafeCell::new(None)
+        }
+    }
+
+    #[inline]
+    pub fn get<'a, C: STC>(&'a self, _context: &C) -> Option<&'a T> {
+        unsafe { &*self.value.get() }.as_ref()
+    }
+
+    /// Gets the contents of the cell from any thread once concurrency has started.
+    ///
+    /// The contents of an initialized cell never change, so they can be shared between threads.
+    /// A cell that is still empty when `Init::into_concurrent` is called stays empty, because it
+    /// could otherwise be written on one thread while being read here on another.
+    ///
+    /// # Example
+    /// ```
+    /// use concurrency_context::{Init, IrqBackend, IrqGuard, SingleThreadOnceCell};
+    /// # struct Arch;
+    /// # unsafe impl IrqBackend for Arch {
+    /// #     type State = ();
+    /// #     fn save_and_disable() {}
+    /// #     unsafe fn restore(_state: ()) {}
+    /// # }
+    /// static G_NAME: SingleThreadOnceCell<&'static str> = SingleThreadOnceCell::new();
+    ///
+    /// let concurrent = unsafe { Init::new() }.into_concurrent();
+    /// IrqGuard::<Arch>::with(|irq| assert_eq!(G_NAME.set(irq, "late"), Err("late")));
+    /// assert_eq!(G_NAME.get_shared(&concurrent), None);
+    /// ```
+    #[inline]
+    pub fn get_shared<'a>(&'a self, _concurrent: &Concurrent) -> Option<&'a T> {
+        unsafe { &*self.value.get() }.as_ref()
+    }
+
+    /// Sets the contents of the cell to `value`.
+    ///
+    /// Returns `Err(value)` if the cell was already initialized, or if concurrency has started.
+    #[inline]
+    pub fn set<C: STC>(&self, context: &C, value: T) -> Result<(), T> {
+        if self.get(context).is_some() || concurrent_started() {
+            return Err(value);
+        }
+        // No references to the contents can exist while the cell is empty
+        unsafe { *self.value.get() = Some(value) };
+        Ok(())
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell was empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` initializes the cell itself, or if the cell is empty and concurrency has
+    /// started.
+    pub fn get_or_init<'a, C: STC, F: FnOnce() -> T>(&'a self, context: &C, f: F) -> &'a T {
+        if let Some(value) = self.get(context) {
+            return value;
+        }
+        if concurrent_started() {
+            panic!("SingleThreadOnceCell initialized after concurrency started");
+        }
+        let value = f();
+        if self.set(context, value).is_err() {
+            panic!("SingleThreadOnceCell initialized re-entrantly");
+        }
+        self.get(context).unwrap()
+    }
+}
+
+impl<T> Default for SingleThreadOnceCell<T> {
+    fn default() -> SingleThreadOnceCell<T> {
+        SingleThreadOnceCell::new()
+    }
+}
+
+/// A value that is initialized on first access in single-thread context.
+///
+/// The initializer runs the first time `force` is called. Later calls return the cached value.
+/// Like `SingleThreadOnceCell`, the returned reference is tied to the lifetime of the lazy value
+/// rather than the context.
+///
+/// # Example
+/// ```
+/// use concurrency_context::SingleThreadLazy;
+/// static G_TABLE: SingleThreadLazy<[u8; 4]> = SingleThreadLazy::new(|| [1, 2, 3, 4]);
+///
+/// let ctx = unsafe { concurrency_context::Init::new() };
+/// assert_eq!(G_TABLE.force(&ctx)[2], 3);
+/// ```
+pub struct SingleThreadLazy<T, F = fn() -> T> {
+    cell: SingleThreadOnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+unsafe impl<T: Send + Sync, F: Send> Sync for SingleThreadLazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> SingleThreadLazy<T, F> {
+    #[inline]
+    pub const fn new(init: F) -> SingleThreadLazy<T, F> {
+        SingleThreadLazy {
+            cell: SingleThreadOnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+
+    /// Returns the value, running the initializer if this is the first access.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the initializer accesses this value itself, if a previous run of the initializer
+    /// panicked, or if the value is not initialized yet and concurrency has started.
+    pub fn force<'a, C: STC>(&'a self, context: &C) -> &'a T {
+        self.cell.get_or_init(context, || match self.init.take() {
+            Some(init) => init(),
+            None => panic!("SingleThreadLazy initialized re-entrantly or after its initializer panicked"),
+        })
+    }
+
+    /// Returns the value from any thread once concurrency has started, if it was initialized.
+    #[inline]
+    pub fn get_shared<'a>(&'a self, concurrent: &Concurrent) -> Option<&'a T> {
+        self.cell.get_shared(concurrent)
+    }
+}
+
+/// A cell whose borrows are checked at compile time through a unique context.
+///
+/// Unlike `SingleThreadRefCell`, there is no borrow flag. Immutable borrows borrow the context
+/// immutably and mutable borrows borrow it mutably, so the borrow checker enforces exclusivity on
+/// the context itself. This requires a context implementing `ExclusiveSTC`, of which only one
+/// value can exist, and the cell is tied to that context type.
+///
+/// # Example
+/// ```
+/// use concurrency_context::{Init, TokenCell};
+/// static G_INT: TokenCell<i32> = TokenCell::new(5);
+/// static G_OTHER: TokenCell<i32> = TokenCell::new(7);
+///
+/// let mut ctx = unsafe { Init::new() };
+/// *G_INT.borrow_mut(&mut ctx) += 1;
+/// assert_eq!(*G_INT.borrow(&ctx), 6);
+///
+/// let [a, b] = TokenCell::borrow_mut_multiple([&G_INT, &G_OTHER], &mut ctx);
+/// core::mem::swap(a, b);
+/// assert_eq!(*G_INT.borrow(&ctx), 7);
+/// ```
+///
+/// The context cannot be used while a mutable borrow is alive:
+/// ```compile_fail
+/// use concurrency_context::{Init, TokenCell};
+/// static G_INT: TokenCell<i32> = TokenCell::new(5);
+///
+/// let mut ctx = unsafe { Init::new() };
+/// let a = G_INT.borrow_mut(&mut ctx);
+/// let b = G_INT.borrow(&ctx);
+/// *a += *b;
+/// ```
+pub struct TokenCell<T, C: ExclusiveSTC = ::Init> {
+    value: UnsafeCell<T>,
+    _context: PhantomData<fn(&C)>,
+}
+
+unsafe impl<T, C: ExclusiveSTC> Sync for TokenCell<T, C> {}
+
+impl<T, C: ExclusiveSTC> TokenCell<T, C> {
+    #[inline]
+    pub const fn new(value: T) -> TokenCell<T, C> {
+        TokenCell {
+            value: UnsafeCell::new(value),
             _context: PhantomData,
         }
     }
+
+    #[inline]
+    pub fn borrow<'a>(&'a self, _context: &'a C) -> &'a T {
+        unsafe { &*self.value.get() }
+    }
+
+    #[inline]
+    pub fn borrow_mut<'a>(&'a self, _context: &'a mut C) -> &'a mut T {
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Mutably borrows several cells at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same cell is passed more than once.
+    pub fn borrow_mut_multiple<'a, const N: usize>(cells: [&'a TokenCell<T, C>; N], _context: &'a mut C) -> [&'a mut T; N] {
+        if mem::size_of::<T>() != 0 {
+            for (i, a) in cells.iter().enumerate() {
+                if cells[i + 1..].iter().any(|b| ptr::eq(*a, *b)) {
+                    panic!("TokenCell borrowed mutably more than once");
+                }
+            }
+        }
+        cells.map(|cell| unsafe { &mut *cell.value.get() })
+    }
+
+    /// Consumes the cell, returning the value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the value without a context, as the mutable borrow of the
+    /// cell guarantees exclusive access.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
 }
 
 #[test]
 fn test_zero_size() {
-    use core::mem;
     static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
 
     let ctx = unsafe { ::Init::new() };
 
-    assert_eq!(mem::size_of_val(&G_INT.value), mem::size_of_val(&G_INT));
+    #[cfg(not(any(feature = "std", feature = "track-borrows")))]
+    assert_eq!(mem::size_of::<(RefCell<i32>, AtomicBool)>(), mem::size_of_val(&G_INT));
     let borrow = G_INT.borrow(&ctx);
     assert_eq!(mem::size_of_val(&borrow), mem::size_of_val(&borrow.value));
+
+    if cfg!(all(concurrency_context_unchecked, not(debug_assertions))) {
+        assert_eq!(mem::size_of::<i32>(), mem::size_of_val(&G_INT.value));
+        assert_eq!(mem::size_of::<&i32>(), mem::size_of_val(&borrow));
+    } else {
+        assert_eq!(mem::size_of::<core::cell::RefCell<i32>>(), mem::size_of_val(&G_INT.value));
+        assert_eq!(mem::size_of::<core::cell::Ref<i32>>(), mem::size_of_val(&borrow));
+    }
+}
+
+#[test]
+fn test_cell_zero_size() {
+    static G_INT: SingleThreadCell<i32> = SingleThreadCell::new(5);
+
+    assert_eq!(mem::size_of::<i32>(), mem::size_of_val(&G_INT));
+}
+
+#[test]
+#[should_panic(expected = "re-entrantly")]
+fn test_lazy_reentrant() {
+    static G_LAZY: SingleThreadLazy<i32> = SingleThreadLazy::new(init);
+    std::thread_local! {
+        // Shared with the initializer, since only one `Init` may exist at a time
+        static CTX: ::Init = unsafe { ::Init::new() };
+    }
+
+    fn init() -> i32 {
+        CTX.with(|ctx| *G_LAZY.force(ctx) + 1)
+    }
+
+    CTX.with(|ctx| G_LAZY.force(ctx));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_owner_thread() {
+    static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+
+    let ctx = unsafe { ::Init::new() };
+    assert_eq!(*G_INT.borrow(&ctx), 5);
+
+    let result = thread::spawn(|| {
+        let ctx = unsafe { ::Init::new() };
+        *G_INT.borrow(&ctx)
+    }).join();
+    assert!(result.is_err());
+}
+
+#[cfg(all(feature = "track-borrows", not(all(concurrency_context_unchecked, not(debug_assertions)))))]
+#[test]
+fn test_track_borrows() {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::string::String;
+    static G_INT: SingleThreadRefCell<i32> = SingleThreadRefCell::new(5);
+
+    let ctx = unsafe { ::Init::new() };
+    let a = G_INT.borrow(&ctx);
+    let line = line!() + 1;
+    let _b = G_INT.borrow(&ctx);
+    drop(a);
+    let err = panic::catch_unwind(AssertUnwindSafe(|| *G_INT.borrow_mut(&ctx))).unwrap_err();
+    let message = err.downcast_ref::<String>().unwrap();
+    assert!(message.contains(&std::format!("last borrowed at {}:{}:", file!(), line)), "{}", message);
 }
@@ -0,0 +1,137 @@
+//! Replacements for `RefCell`, `Ref` and `RefMut` without borrow tracking.
+//!
+//! These are used in place of the `core::cell` types by the `concurrency_context_unchecked` cfg in
+//! release builds. They provide the subset of the `RefCell` API used by this crate, with every
+//! borrow succeeding. Overlapping borrows are then undefined behavior instead of a panic.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+/// Error of a borrow that cannot be checked. Never actually returned.
+#[derive(Debug)]
+pub struct Unchecked;
+
+pub struct RefCell<T> {
+    value: UnsafeCell<T>,
+}
+
+impl<T> RefCell<T> {
+    #[inline]
+    pub const fn new(value: T) -> RefCell<T> {
+        RefCell {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    #[inline]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, Unchecked> {
+        Ok(Ref {
+            value: unsafe { &*self.value.get() },
+        })
+    }
+
+    #[inline]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, Unchecked> {
+        Ok(RefMut {
+            value: unsafe { &mut *self.value.get() },
+        })
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+pub struct Ref<'a, T: ?Sized + 'a> {
+    value: &'a T,
+}
+
+impl<'a, T: ?Sized + 'a> Deref for Ref<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized + 'a> Ref<'a, T> {
+    #[inline]
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(orig: Self, f: F) -> Ref<'a, U> {
+        Ref {
+            value: f(orig.value),
+        }
+    }
+
+    #[inline]
+    pub fn filter_map<U: ?Sized, F: FnOnce(&T) -> Option<&U>>(orig: Self, f: F) -> Result<Ref<'a, U>, Self> {
+        match f(orig.value) {
+            Some(value) => Ok(Ref { value }),
+            None => Err(orig),
+        }
+    }
+
+    #[inline]
+    pub fn map_split<U: ?Sized, V: ?Sized, F: FnOnce(&T) -> (&U, &V)>(orig: Self, f: F) -> (Ref<'a, U>, Ref<'a, V>) {
+        let (a, b) = f(orig.value);
+        (Ref { value: a }, Ref { value: b })
+    }
+}
+
+pub struct RefMut<'a, T: ?Sized + 'a> {
+    value: &'a mut T,
+}
+
+impl<'a, T: ?Sized + 'a> Deref for RefMut<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized + 'a> DerefMut for RefMut<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized + 'a> RefMut<'a, T> {
+    #[inline]
+    pub fn map<U: ?Sized, F: FnOnce(&mut T) -> &mut U>(orig: Self, f: F) -> RefMut<'a, U> {
+        RefMut {
+            value: f(orig.value),
+        }
+    }
+
+    #[inline]
+    pub fn filter_map<U: ?Sized, F: FnOnce(&mut T) -> Option<&mut U>>(orig: Self, f: F) -> Result<RefMut<'a, U>, Self> {
+        // The reference is only used again if `f` returned nothing borrowed from it
+        let value: *mut T = orig.value;
+        match f(unsafe { &mut *value }) {
+            Some(value) => Ok(RefMut { value }),
+            None => Err(RefMut {
+                value: unsafe { &mut *value },
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn map_split<U: ?Sized, V: ?Sized, F: FnOnce(&mut T) -> (&mut U, &mut V)>(orig: Self, f: F) -> (RefMut<'a, U>, RefMut<'a, V>) {
+        let (a, b) = f(orig.value);
+        (RefMut { value: a }, RefMut { value: b })
+    }
+}